@@ -1,16 +1,25 @@
 use anyhow::{bail, ensure, Context, Result};
-use cgmath::{Matrix, Matrix4};
+use cgmath::{Matrix, Matrix4, Vector4};
 use glow::HasContext;
 
-use crate::{camera::AppCamera, Position};
+use crate::{camera::AppCamera, cloud::AtomRenderData, Position};
 
 const SHADER_VERTEX: &'_ str = include_str!("shader/vertex.glsl");
 const SHADER_FRAGMENT: &'_ str = include_str!("shader/fragment.glsl");
 
+/// Half-width of the billboard quad, in world units (matches the old fixed quad size).
+const QUAD_HALF_SIZE: f32 = 0.1;
+
 struct AppBuffers {
     pub va: glow::NativeVertexArray,
-    pub positions: glow::NativeBuffer,
+    /// Static unit-quad corners, shared by every instance.
+    pub quad: glow::NativeBuffer,
+    /// Static 6-index triangle list for the quad, uploaded once.
     pub elements: glow::NativeBuffer,
+    /// Per-instance atom centers, re-uploaded every frame.
+    pub positions: glow::NativeBuffer,
+    /// Per-instance (color, size), packed as rgba with size in `.a`, re-uploaded every frame.
+    pub colors: glow::NativeBuffer,
 }
 
 impl AppBuffers {
@@ -25,7 +34,9 @@ impl AppBuffers {
             Err(e) => bail!("Could not create a buffer: {}", e),
         };
 
+        let corner_attrib_index = get_attrib_location("corner")?;
         let position_attrib_index = get_attrib_location("position")?;
+        let color_attrib_index = get_attrib_location("color")?;
 
         // Vertex Array describes the data layout
         let vao = match unsafe { gl.create_vertex_array() } {
@@ -34,12 +45,13 @@ impl AppBuffers {
         };
         unsafe { gl.bind_vertex_array(Some(vao)) };
 
-        let position_buffer = create_buffer()?;
-        unsafe { gl.bind_buffer(glow::ARRAY_BUFFER, Some(position_buffer)) };
-        unsafe { gl.enable_vertex_attrib_array(position_attrib_index) };
+        // Unit quad corners: one vertex per draw call vertex, shared by every instance.
+        let quad_buffer = create_buffer()?;
+        unsafe { gl.bind_buffer(glow::ARRAY_BUFFER, Some(quad_buffer)) };
+        unsafe { gl.enable_vertex_attrib_array(corner_attrib_index) };
         unsafe {
             gl.vertex_attrib_pointer_f32(
-                position_attrib_index,
+                corner_attrib_index,
                 (size_of::<Position>() / size_of::<f32>()) as i32,
                 glow::FLOAT,
                 false,
@@ -48,64 +60,95 @@ impl AppBuffers {
             )
         };
 
+        let quad: [Position; 4] = [
+            Position::new(-QUAD_HALF_SIZE, -QUAD_HALF_SIZE, 0.0),
+            Position::new(QUAD_HALF_SIZE, -QUAD_HALF_SIZE, 0.0),
+            Position::new(QUAD_HALF_SIZE, QUAD_HALF_SIZE, 0.0),
+            Position::new(-QUAD_HALF_SIZE, QUAD_HALF_SIZE, 0.0),
+        ];
+        let quad_u8: &[u8] = unsafe {
+            ::core::slice::from_raw_parts(quad.as_ptr() as *const u8, size_of_val(&quad))
+        };
+        unsafe { gl.buffer_data_u8_slice(glow::ARRAY_BUFFER, quad_u8, glow::STATIC_DRAW) };
+
+        // Triangle list for the quad: also static, since it never changes shape.
         let element_array_buffer = create_buffer()?;
         unsafe { gl.bind_buffer(glow::ELEMENT_ARRAY_BUFFER, Some(element_array_buffer)) };
 
-        Ok(Self {
-            va: vao,
-            positions: position_buffer,
-            elements: element_array_buffer,
-        })
-    }
-
-    pub fn update_elements(&self, gl: &glow::Context, points: usize) -> Result<i32> {
-        let vertices = points * 6;
-
-        let mut elements: Vec<u32> = Vec::with_capacity(vertices);
-
-        for point in 0..points {
-            let p = point as u32 * 4;
-            elements.extend_from_slice(&[p, p + 1, p + 2, p + 2, p + 3, p]);
-        }
-
+        let elements: [u32; 6] = [0, 1, 2, 2, 3, 0];
         let elements_u8: &[u8] = unsafe {
-            ::core::slice::from_raw_parts(
-                elements.as_ptr() as *const u8,
-                size_of::<u32>() * vertices,
+            ::core::slice::from_raw_parts(elements.as_ptr() as *const u8, size_of_val(&elements))
+        };
+        unsafe {
+            gl.buffer_data_u8_slice(glow::ELEMENT_ARRAY_BUFFER, elements_u8, glow::STATIC_DRAW)
+        };
+
+        // Per-instance atom center, advanced once per instance rather than once per vertex.
+        let position_buffer = create_buffer()?;
+        unsafe { gl.bind_buffer(glow::ARRAY_BUFFER, Some(position_buffer)) };
+        unsafe { gl.enable_vertex_attrib_array(position_attrib_index) };
+        unsafe {
+            gl.vertex_attrib_pointer_f32(
+                position_attrib_index,
+                (size_of::<Position>() / size_of::<f32>()) as i32,
+                glow::FLOAT,
+                false,
+                size_of::<Position>() as i32,
+                0, // Offset into the currently bound buffer
             )
         };
+        unsafe { gl.vertex_attrib_divisor(position_attrib_index, 1) };
 
-        unsafe { gl.bind_buffer(glow::ELEMENT_ARRAY_BUFFER, Some(self.elements)) };
+        // Per-instance (color, size), same cadence as `position`.
+        let color_buffer = create_buffer()?;
+        unsafe { gl.bind_buffer(glow::ARRAY_BUFFER, Some(color_buffer)) };
+        unsafe { gl.enable_vertex_attrib_array(color_attrib_index) };
         unsafe {
-            gl.buffer_data_u8_slice(glow::ELEMENT_ARRAY_BUFFER, elements_u8, glow::DYNAMIC_DRAW)
+            gl.vertex_attrib_pointer_f32(
+                color_attrib_index,
+                (size_of::<Vector4<f32>>() / size_of::<f32>()) as i32,
+                glow::FLOAT,
+                false,
+                size_of::<Vector4<f32>>() as i32,
+                0, // Offset into the currently bound buffer
+            )
         };
+        unsafe { gl.vertex_attrib_divisor(color_attrib_index, 1) };
 
-        Ok(vertices as i32)
+        Ok(Self {
+            va: vao,
+            quad: quad_buffer,
+            elements: element_array_buffer,
+            positions: position_buffer,
+            colors: color_buffer,
+        })
     }
 
-    pub fn update_positions(&self, gl: &glow::Context, points: &[Position]) {
-        let vertices = points.len() * 4;
-
-        let mut positions: Vec<Position> = Vec::with_capacity(vertices);
-
-        for point in points {
-            let a = Position::new(point[0] - 0.1, point[1] - 0.1, point[2]);
-            let b = Position::new(point[0] + 0.1, point[1] - 0.1, point[2]);
-            let c = Position::new(point[0] + 0.1, point[1] + 0.1, point[2]);
-            let d = Position::new(point[0] - 0.1, point[1] + 0.1, point[2]);
-
-            positions.extend_from_slice(&[a, b, c, d]);
-        }
-
+    pub fn update_instances(&self, gl: &glow::Context, atoms: &[AtomRenderData]) {
+        let positions: Vec<Position> = atoms.iter().map(|a| a.position).collect();
         let positions_u8: &[u8] = unsafe {
             ::core::slice::from_raw_parts(
                 positions.as_ptr() as *const u8,
-                size_of::<Position>() * vertices,
+                size_of::<Position>() * positions.len(),
             )
         };
 
         unsafe { gl.bind_buffer(glow::ARRAY_BUFFER, Some(self.positions)) };
         unsafe { gl.buffer_data_u8_slice(glow::ARRAY_BUFFER, positions_u8, glow::DYNAMIC_DRAW) };
+
+        let colors: Vec<Vector4<f32>> = atoms
+            .iter()
+            .map(|a| Vector4::new(a.color.x, a.color.y, a.color.z, a.size))
+            .collect();
+        let colors_u8: &[u8] = unsafe {
+            ::core::slice::from_raw_parts(
+                colors.as_ptr() as *const u8,
+                size_of::<Vector4<f32>>() * colors.len(),
+            )
+        };
+
+        unsafe { gl.bind_buffer(glow::ARRAY_BUFFER, Some(self.colors)) };
+        unsafe { gl.buffer_data_u8_slice(glow::ARRAY_BUFFER, colors_u8, glow::DYNAMIC_DRAW) };
     }
 }
 
@@ -160,6 +203,16 @@ fn create_program(gl: &glow::Context) -> Result<glow::Program> {
     Ok(program)
 }
 
+/// Offscreen render target used by capture mode: a texture-backed framebuffer
+/// rendered into instead of the window's default framebuffer, so frames can be
+/// read back with `read_pixels` regardless of the window's own size or visibility.
+struct CaptureTarget {
+    framebuffer: glow::NativeFramebuffer,
+    texture: glow::NativeTexture,
+    width: u32,
+    height: u32,
+}
+
 pub struct App {
     #[allow(dead_code)] // Even if not accessed, this needs to outlive all GL operations
     gl_ctx: sdl3::video::GLContext,
@@ -170,6 +223,10 @@ pub struct App {
     program: glow::Program,
 
     buffers: AppBuffers,
+    capture: Option<CaptureTarget>,
+
+    width: u32,
+    height: u32,
 }
 
 impl App {
@@ -188,6 +245,10 @@ impl App {
             .opengl()
             .build()?;
 
+        // Capture the mouse so looking around doesn't hit the edge of the screen,
+        // and we get relative `xrel`/`yrel` motion instead of absolute coordinates.
+        sdl.mouse().set_relative_mouse_mode(&window, true);
+
         // This needs to be created before function loading.
         // This should only be dropped after we are done with any GL.
         let gl_ctx = window.gl_create_context()?;
@@ -219,13 +280,111 @@ impl App {
             gl,
             program,
             buffers,
+            capture: None,
+
+            width: w,
+            height: h,
         })
     }
 
+    /// Switch rendering to an offscreen framebuffer at `width`x`height`, so frames can
+    /// be recorded with `read_capture` instead of shown in the (possibly differently
+    /// sized) window.
+    pub fn enable_capture(&mut self, width: u32, height: u32) -> Result<()> {
+        let texture = match unsafe { self.gl.create_texture() } {
+            Ok(texture) => texture,
+            Err(e) => bail!("Could not create a capture texture: {}", e),
+        };
+        unsafe { self.gl.bind_texture(glow::TEXTURE_2D, Some(texture)) };
+        unsafe {
+            self.gl.tex_image_2d(
+                glow::TEXTURE_2D,
+                0,
+                glow::RGBA8 as i32,
+                width as i32,
+                height as i32,
+                0,
+                glow::RGBA,
+                glow::UNSIGNED_BYTE,
+                None,
+            )
+        };
+        unsafe {
+            self.gl
+                .tex_parameter_i32(glow::TEXTURE_2D, glow::TEXTURE_MIN_FILTER, glow::LINEAR as i32)
+        };
+        unsafe {
+            self.gl
+                .tex_parameter_i32(glow::TEXTURE_2D, glow::TEXTURE_MAG_FILTER, glow::LINEAR as i32)
+        };
+
+        let framebuffer = match unsafe { self.gl.create_framebuffer() } {
+            Ok(framebuffer) => framebuffer,
+            Err(e) => bail!("Could not create a capture framebuffer: {}", e),
+        };
+        unsafe { self.gl.bind_framebuffer(glow::FRAMEBUFFER, Some(framebuffer)) };
+        unsafe {
+            self.gl.framebuffer_texture_2d(
+                glow::FRAMEBUFFER,
+                glow::COLOR_ATTACHMENT0,
+                glow::TEXTURE_2D,
+                Some(texture),
+                0,
+            )
+        };
+
+        let status = unsafe { self.gl.check_framebuffer_status(glow::FRAMEBUFFER) };
+        ensure!(
+            status == glow::FRAMEBUFFER_COMPLETE,
+            "Capture framebuffer is not complete"
+        );
+
+        unsafe { self.gl.bind_framebuffer(glow::FRAMEBUFFER, None) };
+
+        self.capture = Some(CaptureTarget {
+            framebuffer,
+            texture,
+            width,
+            height,
+        });
+
+        Ok(())
+    }
+
+    /// Read back the last frame rendered into the capture framebuffer as tightly packed
+    /// RGBA8 rows, bottom row first (OpenGL's convention).
+    pub fn read_capture(&self) -> Result<(Vec<u8>, u32, u32)> {
+        let capture = self
+            .capture
+            .as_ref()
+            .context("capture mode was not enabled via `enable_capture`")?;
+
+        let mut pixels = vec![0u8; (capture.width * capture.height * 4) as usize];
+        unsafe {
+            self.gl
+                .bind_framebuffer(glow::FRAMEBUFFER, Some(capture.framebuffer));
+            self.gl.read_pixels(
+                0,
+                0,
+                capture.width as i32,
+                capture.height as i32,
+                glow::RGBA,
+                glow::UNSIGNED_BYTE,
+                glow::PixelPackData::Slice(&mut pixels),
+            );
+        }
+
+        Ok((pixels, capture.width, capture.height))
+    }
+
     pub fn poll_iter(&mut self) -> sdl3::event::EventPollIterator {
         self.event_pump.poll_iter()
     }
 
+    pub fn keyboard_state(&self) -> sdl3::keyboard::KeyboardState<'_> {
+        self.event_pump.keyboard_state()
+    }
+
     pub fn update_uniforms(&self, model: &Matrix4<f32>, camera: &AppCamera) -> Result<()> {
         let get_uniform_location =
             |name: &str| unsafe { self.gl.get_uniform_location(self.program, name) };
@@ -274,16 +433,31 @@ impl App {
         Ok(())
     }
 
-    pub fn render_frame(&self, points: &[Position]) -> Result<()> {
-        let vertices = self.buffers.update_elements(&self.gl, points.len())?;
-        self.buffers.update_positions(&self.gl, points);
+    pub fn render_frame(&self, atoms: &[AtomRenderData]) -> Result<()> {
+        self.buffers.update_instances(&self.gl, atoms);
+
+        let (framebuffer, width, height) = match &self.capture {
+            Some(capture) => (Some(capture.framebuffer), capture.width, capture.height),
+            None => (None, self.width, self.height),
+        };
+
+        unsafe { self.gl.bind_framebuffer(glow::FRAMEBUFFER, framebuffer) };
+        unsafe { self.gl.viewport(0, 0, width as i32, height as i32) };
 
         unsafe { self.gl.clear(glow::COLOR_BUFFER_BIT) };
         unsafe {
-            self.gl
-                .draw_elements(glow::TRIANGLES, vertices, glow::UNSIGNED_INT, 0)
+            self.gl.draw_elements_instanced(
+                glow::TRIANGLES,
+                6,
+                glow::UNSIGNED_INT,
+                0,
+                atoms.len() as i32,
+            )
         };
-        self.window.gl_swap_window();
+
+        if self.capture.is_none() {
+            self.window.gl_swap_window();
+        }
 
         Ok(())
     }
@@ -291,8 +465,15 @@ impl App {
 
 impl Drop for App {
     fn drop(&mut self) {
+        if let Some(capture) = self.capture.take() {
+            unsafe { self.gl.delete_framebuffer(capture.framebuffer) };
+            unsafe { self.gl.delete_texture(capture.texture) };
+        }
+
         unsafe { self.gl.delete_buffer(self.buffers.positions) };
+        unsafe { self.gl.delete_buffer(self.buffers.colors) };
         unsafe { self.gl.delete_buffer(self.buffers.elements) };
+        unsafe { self.gl.delete_buffer(self.buffers.quad) };
         unsafe { self.gl.delete_vertex_array(self.buffers.va) };
         unsafe { self.gl.delete_program(self.program) };
     }
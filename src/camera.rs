@@ -1,26 +1,130 @@
-use cgmath::{Deg, Matrix4, Rad, Vector3};
+use cgmath::{Deg, InnerSpace, Matrix4, Rad, Vector3, Zero};
 
-use crate::Position;
+use crate::{Direction, Position};
+
+/// How the camera reacts to mouse/keyboard input.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum CameraMode {
+    /// WASD moves `position`, the mouse looks around; `center` follows `position + front`.
+    Fly,
+    /// `center` stays fixed, the mouse orbits `position` around it at a constant `radius`.
+    Orbit,
+}
 
 pub struct AppCamera {
     pub position: Position,
     pub center: Position,
     pub fovy: Rad<f32>,
     pub aspect: f32,
+
+    pub mode: CameraMode,
+    pub yaw: Rad<f32>,
+    pub pitch: Rad<f32>,
+    pub radius: f32,
+
+    pub movement_speed: f32,
+    pub mouse_sensitivity: f32,
 }
 
 impl AppCamera {
+    const WORLD_UP: Vector3<f32> = Vector3::new(0.0, 1.0, 0.0);
+    const PITCH_LIMIT: Rad<f32> = Rad(89.0 * ::std::f32::consts::PI / 180.0);
+    const FOVY_MIN: Rad<f32> = Rad(1.0 * ::std::f32::consts::PI / 180.0);
+    const FOVY_MAX: Rad<f32> = Rad(90.0 * ::std::f32::consts::PI / 180.0);
+    /// Fovy change per unit of mouse wheel scroll, in radians.
+    const ZOOM_SENSITIVITY: f32 = 2.0 * ::std::f32::consts::PI / 180.0;
+
     pub fn new(aspect: f32) -> Self {
+        let position = Position::new(1.0, 1.0, 10.0);
+        let center = Position::new(0.0, 0.0, 0.0);
+
+        let front = (center - position).normalize();
+        let yaw = Rad(front.z.atan2(front.x));
+        let pitch = Rad(front.y.asin());
+        let radius = (position - center).magnitude();
+
         Self {
-            position: Position::new(1.0, 1.0, 10.0),
-            center: Position::new(0.0, 0.0, 0.0),
+            position,
+            center,
             fovy: Deg(45.0).into(),
             aspect,
+
+            mode: CameraMode::Fly,
+            yaw,
+            pitch,
+            radius,
+
+            movement_speed: 3.0,
+            mouse_sensitivity: 0.0025,
+        }
+    }
+
+    /// Direction the camera is looking in, derived from `yaw`/`pitch`.
+    pub fn front(&self) -> Direction {
+        Direction::new(
+            self.yaw.0.cos() * self.pitch.0.cos(),
+            self.pitch.0.sin(),
+            self.yaw.0.sin() * self.pitch.0.cos(),
+        )
+        .normalize()
+    }
+
+    pub fn right(&self) -> Direction {
+        self.front().cross(Self::WORLD_UP).normalize()
+    }
+
+    pub fn toggle_mode(&mut self) {
+        self.mode = match self.mode {
+            CameraMode::Fly => CameraMode::Orbit,
+            CameraMode::Orbit => CameraMode::Fly,
+        };
+    }
+
+    /// Apply relative mouse motion to `yaw`/`pitch` and update `position`/`center`
+    /// according to the current `mode`.
+    pub fn look(&mut self, dx: f32, dy: f32) {
+        self.yaw += Rad(dx * self.mouse_sensitivity);
+        self.pitch -= Rad(dy * self.mouse_sensitivity);
+
+        if self.pitch > Self::PITCH_LIMIT {
+            self.pitch = Self::PITCH_LIMIT;
+        }
+        if self.pitch < -Self::PITCH_LIMIT {
+            self.pitch = -Self::PITCH_LIMIT;
+        }
+
+        match self.mode {
+            CameraMode::Fly => self.center = self.position + self.front(),
+            CameraMode::Orbit => self.position = self.center - self.front() * self.radius,
+        }
+    }
+
+    /// Move `position` (and, in `Fly` mode, `center`) by `direction * movement_speed * delta`.
+    /// Has no effect in `Orbit` mode, where `position` is derived from `yaw`/`pitch` instead.
+    pub fn translate(&mut self, direction: Direction, delta: f32) {
+        if self.mode != CameraMode::Fly || direction.is_zero() {
+            return;
+        }
+
+        let offset = direction.normalize() * self.movement_speed * delta;
+        self.position += offset;
+        self.center += offset;
+    }
+
+    /// Zoom via the mouse wheel, clamped to a sane `fovy` range.
+    pub fn zoom(&mut self, wheel_delta: f32) {
+        self.fovy -= Rad(wheel_delta * Self::ZOOM_SENSITIVITY);
+
+        if self.fovy < Self::FOVY_MIN {
+            self.fovy = Self::FOVY_MIN;
+        }
+        if self.fovy > Self::FOVY_MAX {
+            self.fovy = Self::FOVY_MAX;
         }
     }
 
     pub fn view(&self) -> Matrix4<f32> {
-        Matrix4::<f32>::from_translation(Vector3::new(0.0, 0.0, -4.0))
+        Matrix4::look_at_rh(self.position, self.center, Self::WORLD_UP)
     }
 
     pub fn projection(&self) -> Matrix4<f32> {
@@ -1,17 +1,46 @@
 use std::f32::consts::PI;
 
 use cgmath::{EuclideanSpace, InnerSpace, Point3, Vector3, Zero};
-use rand::Rng;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use crate::octree::Octree;
 
 pub type Position = Point3<f32>;
 pub type Velocity = Vector3<f32>;
 pub type Acceleration = Vector3<f32>;
 pub type Force = Vector3<f32>;
+pub type Color = Vector3<f32>;
+
+/// Per-atom data the renderer needs to draw a billboard: where it is, what color
+/// it should be, and how big.
+pub struct AtomRenderData {
+    pub position: Position,
+    pub color: Color,
+    pub size: f32,
+}
+
+/// Maps `charge` (normalized to `[-1, 1]`) to a blue (negative) <-> white <-> red
+/// (positive) diverging color.
+fn charge_to_color(charge: f32) -> Color {
+    let blue = Color::new(0.0, 0.0, 1.0);
+    let white = Color::new(1.0, 1.0, 1.0);
+    let red = Color::new(1.0, 0.0, 0.0);
+
+    if charge <= 0.0 {
+        let t = charge + 1.0;
+        blue + (white - blue) * t
+    } else {
+        white + (red - white) * charge
+    }
+}
 
 #[derive(Clone)]
 pub struct Atom {
     pub position: Position,
     pub velocity: Velocity,
+    /// Acceleration from the last force evaluation; only meaningful for the
+    /// `Verlet` integrator, which needs last step's acceleration to update position.
+    pub acceleration: Acceleration,
     pub mass: f32,
     pub charge: f32,
 }
@@ -27,6 +56,7 @@ impl Atom {
         Self {
             position,
             velocity: Velocity::zero(),
+            acceleration: Acceleration::zero(),
             mass: 1.0,
             charge: 1.0,
         }
@@ -37,30 +67,89 @@ impl Atom {
         self.position += delta * self.velocity;
     }
 
-    pub fn find_gravity(&self, other: &Atom) -> Force {
+    /// `epsilon` is the Plummer softening length: it keeps the force finite as
+    /// `other` approaches `self` instead of blowing up to infinity.
+    pub fn find_gravity(&self, other: &Atom, epsilon: f32) -> Force {
         // By default, attract.
         let dir = other.position - self.position;
-        let factor = Self::G * self.mass * other.mass / dir.magnitude2();
+        let factor = Self::G * self.mass * other.mass / (dir.magnitude2() + epsilon * epsilon);
         return dir * factor;
     }
 
-    pub fn find_magnetism(&self, other: &Atom) -> Force {
+    pub fn find_magnetism(&self, other: &Atom, epsilon: f32) -> Force {
         // By default, repel.
         // If charges have opposing signs, this will turn into attraction.
         let dir = self.position - other.position;
-        let factor = Self::μ * self.charge * other.charge / 4.0 / PI / dir.magnitude2();
+        let factor = Self::μ * self.charge * other.charge
+            / 4.0
+            / PI
+            / (dir.magnitude2() + epsilon * epsilon);
         return dir * factor;
     }
 }
 
+/// Numerical scheme used to advance atoms from forces to new positions/velocities.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Integrator {
+    /// Semi-implicit Euler: cheap, but leaks energy and can blow up under stiff forces.
+    Euler,
+    /// Velocity-Verlet: averages the acceleration at the old position (cached from the
+    /// previous step) with the one at the new position, which keeps energy roughly
+    /// conserved over long runs.
+    Verlet,
+}
+
+/// Default Barnes-Hut opening angle: nodes with `width / distance < theta`
+/// are treated as a single pseudo-atom instead of being recursed into.
+const DEFAULT_THETA: f32 = 0.5;
+/// Default Plummer softening length.
+const DEFAULT_EPSILON: f32 = 0.05;
+const DEFAULT_INTEGRATOR: Integrator = Integrator::Verlet;
+
 pub struct Cloud {
     atoms: Vec<Atom>,
+    theta: f32,
+    epsilon: f32,
+    integrator: Integrator,
+    /// Whether `Atom::acceleration` has been seeded yet; only relevant to `Verlet`.
+    verlet_primed: bool,
 }
 
 impl Cloud {
     pub fn new(count: usize) -> Self {
+        Self::new_with_opening_angle(count, DEFAULT_THETA)
+    }
+
+    /// Like `new`, but atom positions are drawn from a seeded, reproducible RNG instead
+    /// of the thread-local one. Used by capture mode, where a run has to be reproducible
+    /// frame-for-frame.
+    pub fn new_with_seed(count: usize, seed: u64) -> Self {
+        let mut rng = StdRng::seed_from_u64(seed);
+        Self::from_rng(
+            count,
+            DEFAULT_THETA,
+            DEFAULT_EPSILON,
+            DEFAULT_INTEGRATOR,
+            &mut rng,
+        )
+    }
+
+    pub fn new_with_opening_angle(count: usize, theta: f32) -> Self {
+        Self::new_with_params(count, theta, DEFAULT_EPSILON, DEFAULT_INTEGRATOR)
+    }
+
+    pub fn new_with_params(count: usize, theta: f32, epsilon: f32, integrator: Integrator) -> Self {
         let mut rng = rand::rng();
+        Self::from_rng(count, theta, epsilon, integrator, &mut rng)
+    }
 
+    fn from_rng(
+        count: usize,
+        theta: f32,
+        epsilon: f32,
+        integrator: Integrator,
+        rng: &mut impl Rng,
+    ) -> Self {
         let mut atoms = Vec::with_capacity(count);
 
         for _ in 0..count {
@@ -69,10 +158,18 @@ impl Cloud {
                 rng.random_range(-1.0..=1.0),
                 rng.random_range(-1.0..=1.0),
             );
-            atoms.push(Atom::new(pos));
+            let mut atom = Atom::new(pos);
+            atom.charge = rng.random_range(-1.0..=1.0);
+            atoms.push(atom);
         }
 
-        Self { atoms }
+        Self {
+            atoms,
+            theta,
+            epsilon,
+            integrator,
+            verlet_primed: false,
+        }
     }
 
     pub fn step(&mut self, delta: f32) {
@@ -87,22 +184,49 @@ impl Cloud {
             atom.position += center_of_mass * delta * RECENTER;
         }
 
-        let atoms_tmp = self.atoms.clone();
+        // 2. Apply forces, approximated with a Barnes-Hut octree built over the current positions
+        match self.integrator {
+            Integrator::Euler => self.step_euler(delta),
+            Integrator::Verlet => self.step_verlet(delta),
+        }
 
-        // 2. Apply forces
-        for (a, atom) in self.atoms.iter_mut().enumerate() {
-            let mut force = Force::zero();
-            for (o, other) in atoms_tmp.iter().enumerate() {
-                if a == o {
-                    continue;
-                }
-                force += atom.find_gravity(other);
-                force += atom.find_magnetism(other);
-            }
+        self.sort();
+    }
+
+    fn step_euler(&mut self, delta: f32) {
+        let octree = Octree::build(&self.atoms);
+
+        for (index, atom) in self.atoms.iter_mut().enumerate() {
+            let force = octree.force_on(index, atom, self.theta, self.epsilon);
             atom.step(force, delta);
         }
+    }
 
-        self.sort();
+    fn step_verlet(&mut self, delta: f32) {
+        if !self.verlet_primed {
+            let octree = Octree::build(&self.atoms);
+            for (index, atom) in self.atoms.iter_mut().enumerate() {
+                let force = octree.force_on(index, atom, self.theta, self.epsilon);
+                atom.acceleration = force / atom.mass;
+            }
+            self.verlet_primed = true;
+        }
+
+        for atom in &mut self.atoms {
+            let a_old = atom.acceleration;
+            atom.position += delta * atom.velocity + 0.5 * delta * delta * a_old;
+        }
+
+        let octree = Octree::build(&self.atoms);
+
+        for (index, atom) in self.atoms.iter_mut().enumerate() {
+            let a_old = atom.acceleration;
+            let force = octree.force_on(index, atom, self.theta, self.epsilon);
+            let a_new = force / atom.mass;
+
+            atom.velocity += 0.5 * delta * (a_old + a_new);
+            atom.acceleration = a_new;
+        }
     }
 
     fn sort(&mut self) {
@@ -110,7 +234,33 @@ impl Cloud {
         self.atoms.sort_by(s);
     }
 
-    pub fn positions(&self) -> Vec<Position> {
-        self.atoms.iter().map(|a| a.position).collect()
+    /// Position, color, and size for each atom, for the renderer to turn into billboards.
+    /// Color diverges blue (negative charge) -> white -> red (positive charge); size grows
+    /// with speed so fast-moving atoms read as bigger than a slow, cold cloud.
+    pub fn render_data(&self) -> Vec<AtomRenderData> {
+        let max_charge = self
+            .atoms
+            .iter()
+            .map(|a| a.charge.abs())
+            .fold(f32::EPSILON, f32::max);
+        let max_speed = self
+            .atoms
+            .iter()
+            .map(|a| a.velocity.magnitude())
+            .fold(f32::EPSILON, f32::max);
+
+        self.atoms
+            .iter()
+            .map(|atom| {
+                let charge = (atom.charge / max_charge).clamp(-1.0, 1.0);
+                let speed = (atom.velocity.magnitude() / max_speed).clamp(0.0, 1.0);
+
+                AtomRenderData {
+                    position: atom.position,
+                    color: charge_to_color(charge),
+                    size: 0.75 + 0.5 * speed,
+                }
+            })
+            .collect()
     }
 }
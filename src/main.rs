@@ -1,7 +1,8 @@
+use std::path::PathBuf;
 use std::time::{Duration, Instant};
 
-use anyhow::Result;
-use cgmath::{Matrix4, Point3, SquareMatrix, Vector3};
+use anyhow::{bail, Context, Result};
+use cgmath::{Matrix4, Point3, SquareMatrix, Vector3, Zero};
 use cloud::Cloud;
 
 use crate::{app::App, camera::AppCamera};
@@ -12,8 +13,61 @@ pub type Direction = Vector3<f32>;
 pub mod app;
 pub mod camera;
 pub mod cloud;
+pub mod octree;
+
+/// RNG seed used for `--capture` runs, so a capture is reproducible across invocations.
+const CAPTURE_SEED: u64 = 42;
+
+struct Args {
+    /// Directory to write numbered PNG frames into; enables capture mode when set.
+    capture: Option<PathBuf>,
+    /// How many frames to render in capture mode.
+    frames: u32,
+}
+
+fn parse_args() -> Result<Args> {
+    let mut capture = None;
+    let mut frames = 300;
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--capture" => {
+                let dir = args
+                    .next()
+                    .context("--capture requires a directory argument")?;
+                capture = Some(PathBuf::from(dir));
+            }
+            "--frames" => {
+                let n = args.next().context("--frames requires a number argument")?;
+                frames = n.parse().context("--frames must be a number")?;
+            }
+            other => bail!("Unknown argument: {}", other),
+        }
+    }
+
+    Ok(Args { capture, frames })
+}
+
+/// Write one `read_capture` readback (bottom row first, per OpenGL convention) out as a PNG.
+fn save_capture_frame(path: &std::path::Path, pixels: &[u8], width: u32, height: u32) -> Result<()> {
+    let row_bytes = width as usize * 4;
+    let mut flipped = vec![0u8; pixels.len()];
+
+    for row in 0..height as usize {
+        let src = row * row_bytes;
+        let dst = (height as usize - 1 - row) * row_bytes;
+        flipped[dst..dst + row_bytes].copy_from_slice(&pixels[src..src + row_bytes]);
+    }
+
+    image::save_buffer(path, &flipped, width, height, image::ColorType::Rgba8)?;
+
+    Ok(())
+}
 
 pub fn main() -> Result<()> {
+    let args = parse_args()?;
+
     let w = 1600;
     let h = 1200;
     let aspect = w as f32 / h as f32;
@@ -24,32 +78,94 @@ pub fn main() -> Result<()> {
     let frame_delta = 1.0 / frames_per_second as f32;
     let frame_duration = Duration::new(0, 1_000_000_000u32 / frames_per_second);
 
-    let mut cloud = Cloud::new(20);
+    let mut cloud = match &args.capture {
+        Some(_) => Cloud::new_with_seed(20, CAPTURE_SEED),
+        None => Cloud::new(20),
+    };
 
-    let camera = AppCamera::new(aspect);
+    let mut camera = AppCamera::new(aspect);
     let model = Matrix4::<f32>::identity();
 
+    if let Some(dir) = &args.capture {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("Could not create capture directory '{}'", dir.display()))?;
+        app.enable_capture(w, h)?;
+
+        for frame in 0..args.frames {
+            app.update_uniforms(&model, &camera)?;
+            app.render_frame(&cloud.render_data())?;
+
+            let (pixels, width, height) = app.read_capture()?;
+            let path = dir.join(format!("frame_{:05}.png", frame));
+            save_capture_frame(&path, &pixels, width, height)?;
+
+            cloud.step(frame_delta);
+        }
+
+        return Ok(());
+    }
+
     'quit: loop {
+        let mut mouse_dx = 0.0f32;
+        let mut mouse_dy = 0.0f32;
+        let mut wheel_dy = 0.0f32;
+
         {
             use sdl3::event::Event;
             use sdl3::keyboard::Keycode;
 
             for event in app.poll_iter() {
-                if let Event::Quit { .. }
-                | Event::KeyDown {
-                    keycode: Some(Keycode::Escape),
-                    ..
-                } = event
-                {
-                    break 'quit;
+                match event {
+                    Event::Quit { .. }
+                    | Event::KeyDown {
+                        keycode: Some(Keycode::Escape),
+                        ..
+                    } => break 'quit,
+                    Event::KeyDown {
+                        keycode: Some(Keycode::Tab),
+                        ..
+                    } => camera.toggle_mode(),
+                    Event::MouseMotion { xrel, yrel, .. } => {
+                        mouse_dx += xrel;
+                        mouse_dy += yrel;
+                    }
+                    Event::MouseWheel { y, .. } => {
+                        wheel_dy += y;
+                    }
+                    _ => {}
                 }
             }
         }
 
+        camera.look(mouse_dx, mouse_dy);
+        camera.zoom(wheel_dy);
+
+        {
+            use sdl3::keyboard::Scancode;
+
+            let keys = app.keyboard_state();
+            let mut movement = Vector3::<f32>::zero();
+
+            if keys.is_scancode_pressed(Scancode::W) {
+                movement += camera.front();
+            }
+            if keys.is_scancode_pressed(Scancode::S) {
+                movement -= camera.front();
+            }
+            if keys.is_scancode_pressed(Scancode::D) {
+                movement += camera.right();
+            }
+            if keys.is_scancode_pressed(Scancode::A) {
+                movement -= camera.right();
+            }
+
+            camera.translate(movement, frame_delta);
+        }
+
         let instant_start = Instant::now();
 
         app.update_uniforms(&model, &camera)?;
-        app.render_frame(&cloud.positions())?;
+        app.render_frame(&cloud.render_data())?;
 
         let instant_end = Instant::now();
         let duration_rendering = instant_end - instant_start;
@@ -0,0 +1,197 @@
+use cgmath::{EuclideanSpace, InnerSpace, Vector3, Zero};
+
+use crate::cloud::{Atom, Force, Position};
+
+/// How deep the tree is allowed to subdivide before atoms occupying (almost)
+/// the same point are just piled into one leaf instead of recursing forever.
+const MAX_DEPTH: u32 = 32;
+
+/// Axis-aligned cube region of space a node is responsible for.
+#[derive(Clone, Copy)]
+struct Bounds {
+    center: Position,
+    half_width: f32,
+}
+
+impl Bounds {
+    fn containing(atoms: &[Atom]) -> Self {
+        let mut half_width: f32 = 1.0;
+        for atom in atoms {
+            half_width = half_width
+                .max(atom.position.x.abs())
+                .max(atom.position.y.abs())
+                .max(atom.position.z.abs());
+        }
+
+        Self {
+            center: Position::new(0.0, 0.0, 0.0),
+            // Pad a bit so atoms sitting exactly on the boundary are unambiguous.
+            half_width: half_width * 1.01,
+        }
+    }
+
+    fn octant_index(&self, position: Position) -> usize {
+        let mut index = 0;
+        if position.x >= self.center.x {
+            index |= 1;
+        }
+        if position.y >= self.center.y {
+            index |= 2;
+        }
+        if position.z >= self.center.z {
+            index |= 4;
+        }
+        index
+    }
+
+    fn octant(&self, index: usize) -> Self {
+        let half = self.half_width / 2.0;
+        let sign = |bit: usize| if index & bit != 0 { 1.0 } else { -1.0 };
+
+        Self {
+            center: self.center
+                + Vector3::new(sign(1) * half, sign(2) * half, sign(4) * half),
+            half_width: half,
+        }
+    }
+}
+
+#[derive(Default)]
+enum OctreeNode {
+    #[default]
+    Empty,
+    /// Atoms that ended up in (almost) the same cell; stored with their
+    /// original index so the caller can skip itself during force lookup.
+    Leaf(Vec<(usize, Atom)>),
+    Internal {
+        children: Box<[OctreeNode; 8]>,
+        mass: f32,
+        charge: f32,
+        center_of_mass: Position,
+        width: f32,
+    },
+}
+
+impl OctreeNode {
+    fn insert(&mut self, index: usize, atom: Atom, bounds: Bounds, depth: u32) {
+        match self {
+            OctreeNode::Empty => {
+                *self = OctreeNode::Leaf(vec![(index, atom)]);
+                return;
+            }
+            OctreeNode::Leaf(occupants) if depth >= MAX_DEPTH => {
+                occupants.push((index, atom));
+                return;
+            }
+            _ => {}
+        }
+
+        if matches!(self, OctreeNode::Leaf(_)) {
+            let occupants = match std::mem::replace(self, OctreeNode::Empty) {
+                OctreeNode::Leaf(occupants) => occupants,
+                _ => unreachable!(),
+            };
+
+            *self = OctreeNode::Internal {
+                children: Box::default(),
+                mass: 0.0,
+                charge: 0.0,
+                center_of_mass: Position::new(0.0, 0.0, 0.0),
+                width: bounds.half_width * 2.0,
+            };
+
+            for (i, a) in occupants {
+                self.insert(i, a, bounds, depth);
+            }
+        }
+
+        if let OctreeNode::Internal {
+            children,
+            mass,
+            charge,
+            center_of_mass,
+            ..
+        } = self
+        {
+            let total_mass = *mass + atom.mass;
+            *center_of_mass = Position::from_vec(
+                (center_of_mass.to_vec() * *mass + atom.position.to_vec() * atom.mass)
+                    / total_mass,
+            );
+            *mass = total_mass;
+            *charge += atom.charge;
+
+            let octant = bounds.octant_index(atom.position);
+            children[octant].insert(index, atom, bounds.octant(octant), depth + 1);
+        }
+    }
+
+    fn force_on(&self, index: usize, atom: &Atom, theta: f32, epsilon: f32) -> Force {
+        match self {
+            OctreeNode::Empty => Force::zero(),
+            OctreeNode::Leaf(occupants) => {
+                let mut force = Force::zero();
+                for (i, other) in occupants {
+                    if *i == index {
+                        continue;
+                    }
+                    force += atom.find_gravity(other, epsilon);
+                    force += atom.find_magnetism(other, epsilon);
+                }
+                force
+            }
+            OctreeNode::Internal {
+                children,
+                mass,
+                charge,
+                center_of_mass,
+                width,
+            } => {
+                let distance = (*center_of_mass - atom.position).magnitude();
+
+                if distance > 0.0 && width / distance < theta {
+                    let pseudo = Atom {
+                        position: *center_of_mass,
+                        velocity: Force::zero(),
+                        acceleration: Force::zero(),
+                        mass: *mass,
+                        charge: *charge,
+                    };
+                    atom.find_gravity(&pseudo, epsilon) + atom.find_magnetism(&pseudo, epsilon)
+                } else {
+                    let mut force = Force::zero();
+                    for child in children.iter() {
+                        force += child.force_on(index, atom, theta, epsilon);
+                    }
+                    force
+                }
+            }
+        }
+    }
+}
+
+/// A Barnes-Hut octree built once per simulation step, used to approximate
+/// the O(N) -> O(log N) per-body force instead of summing over every other atom.
+pub struct Octree {
+    root: OctreeNode,
+}
+
+impl Octree {
+    pub fn build(atoms: &[Atom]) -> Self {
+        let bounds = Bounds::containing(atoms);
+
+        let mut root = OctreeNode::Empty;
+        for (index, atom) in atoms.iter().enumerate() {
+            root.insert(index, atom.clone(), bounds, 0);
+        }
+
+        Self { root }
+    }
+
+    /// Force on `atom` (originally at `index` in the atoms this tree was built from),
+    /// approximated by collapsing any node with `width / distance < theta` into a
+    /// single pseudo-atom at its center of mass.
+    pub fn force_on(&self, index: usize, atom: &Atom, theta: f32, epsilon: f32) -> Force {
+        self.root.force_on(index, atom, theta, epsilon)
+    }
+}